@@ -0,0 +1,377 @@
+//! Parsing for InfluxDB's [annotated CSV] Flux query response format.
+//!
+//! The response body returned by `/api/v2/query` is plain RFC-4180 CSV with
+//! a handful of extra conventions layered on top:
+//!
+//! * Three annotation rows, each starting with `#`, precede the header row:
+//!   `#datatype` gives the Flux type of every column, `#group` marks which
+//!   columns are part of the grouping key, and `#default` supplies the value
+//!   to use for a column when a data row leaves that cell empty.
+//! * The header row that follows names every column (the `table` column
+//!   groups rows that belong to the same result table).
+//! * A blank line separates independent result sets, each of which repeats
+//!   its own annotation and header rows before its data rows resume.
+//!
+//! [annotated CSV]: https://docs.influxdata.com/influxdb/v2/reference/syntax/annotated-csv/
+
+use csv::ReaderBuilder;
+use serde_json::{Map, Number, Value};
+use snafu::{OptionExt, ResultExt, Snafu};
+
+/// Errors that can occur parsing an annotated-CSV response body.
+#[derive(Debug, Snafu)]
+pub enum AnnotatedCsvError {
+    /// The underlying CSV text could not be tokenized.
+    #[snafu(display("error reading CSV: {}", source))]
+    Csv { source: csv::Error },
+
+    /// A result table's data rows appeared before a `#datatype` annotation
+    /// row and header row had been seen for it.
+    #[snafu(display("data row appeared before a #datatype and header row were seen"))]
+    MissingAnnotations,
+
+    /// A cell held a value that couldn't be coerced to its column's
+    /// `#datatype`.
+    #[snafu(display(
+        "value {:?} in column `{}` is not valid for datatype `{}`",
+        value,
+        column,
+        datatype
+    ))]
+    InvalidValue {
+        value: String,
+        column: String,
+        datatype: String,
+    },
+}
+
+/// One of the Flux types that may appear in a `#datatype` annotation row.
+#[derive(Debug, Clone, PartialEq)]
+enum Datatype {
+    String,
+    Long,
+    UnsignedLong,
+    Double,
+    Boolean,
+    Duration,
+    Base64Binary,
+    DateTimeRfc3339,
+}
+
+impl Datatype {
+    fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "string" => Some(Self::String),
+            "long" => Some(Self::Long),
+            "unsignedLong" => Some(Self::UnsignedLong),
+            "double" => Some(Self::Double),
+            "boolean" => Some(Self::Boolean),
+            "duration" => Some(Self::Duration),
+            "base64Binary" => Some(Self::Base64Binary),
+            "dateTime:RFC3339" => Some(Self::DateTimeRfc3339),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::String => "string",
+            Self::Long => "long",
+            Self::UnsignedLong => "unsignedLong",
+            Self::Double => "double",
+            Self::Boolean => "boolean",
+            Self::Duration => "duration",
+            Self::Base64Binary => "base64Binary",
+            Self::DateTimeRfc3339 => "dateTime:RFC3339",
+        }
+    }
+
+    /// Coerce a single CSV cell, already resolved against `#default`, into
+    /// the JSON value this datatype maps to.
+    fn coerce(&self, column: &str, value: &str) -> Result<Value, AnnotatedCsvError> {
+        if value.is_empty() && *self != Self::String {
+            return Ok(Value::Null);
+        }
+
+        let invalid = || InvalidValueSnafu {
+            value: value.to_string(),
+            column: column.to_string(),
+            datatype: self.name().to_string(),
+        };
+
+        Ok(match self {
+            Self::String => Value::String(value.to_string()),
+            Self::Long => Value::Number(value.parse::<i64>().ok().context(invalid())?.into()),
+            Self::UnsignedLong => {
+                Value::Number(value.parse::<u64>().ok().context(invalid())?.into())
+            }
+            Self::Double => {
+                let parsed = value.parse::<f64>().ok().context(invalid())?;
+                // `Inf`/`-Inf`/`NaN` parse fine as `f64` but have no JSON
+                // representation, so `Number::from_f64` rejects them; surface
+                // that as `InvalidValue` rather than silently turning a
+                // present value into `Null`.
+                Value::Number(Number::from_f64(parsed).context(invalid())?)
+            }
+            Self::Boolean => Value::Bool(match value {
+                "true" => true,
+                "false" => false,
+                _ => return invalid().fail(),
+            }),
+            // Flux encodes durations as duration literals (e.g. `1h0m0s`),
+            // not a plain integer, so keep the literal as a string the same
+            // way `DateTimeRfc3339` does and let callers parse it as needed.
+            Self::Duration => Value::String(value.to_string()),
+            Self::Base64Binary => {
+                let bytes = base64_decode(value).context(invalid())?;
+                Value::Array(bytes.into_iter().map(|b| Value::Number(b.into())).collect())
+            }
+            // RFC3339 timestamps are kept as strings; callers deserialize
+            // them into whatever time type their record uses.
+            Self::DateTimeRfc3339 => Value::String(value.to_string()),
+        })
+    }
+}
+
+/// A minimal base64 decoder, just enough to cover the `base64Binary` Flux
+/// datatype without pulling in a whole base64 crate for one column type.
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    fn value(byte: u8) -> Option<u8> {
+        match byte {
+            b'A'..=b'Z' => Some(byte - b'A'),
+            b'a'..=b'z' => Some(byte - b'a' + 26),
+            b'0'..=b'9' => Some(byte - b'0' + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let input = input.trim_end_matches('=').as_bytes();
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    for chunk in input.chunks(4) {
+        let mut buf = [0u8; 4];
+        for (i, &b) in chunk.iter().enumerate() {
+            buf[i] = value(b)?;
+        }
+        out.push((buf[0] << 2) | (buf[1] >> 4));
+        if chunk.len() > 2 {
+            out.push((buf[1] << 4) | (buf[2] >> 2));
+        }
+        if chunk.len() > 3 {
+            out.push((buf[2] << 6) | buf[3]);
+        }
+    }
+    Some(out)
+}
+
+/// A result table's annotations: the datatype, default value, and name of
+/// each column, in column order.
+#[derive(Debug, Default)]
+struct TableSchema {
+    datatypes: Vec<Option<Datatype>>,
+    defaults: Vec<String>,
+    columns: Vec<String>,
+}
+
+/// Parse an annotated-CSV response body into one JSON object per data row.
+///
+/// Each object's keys are the result's column names (as given by its header
+/// row) and its values have been coerced according to the matching
+/// `#datatype` annotation, with empty cells filled in from `#default`. The
+/// `table` column that Flux uses to group rows is passed through like any
+/// other column, so callers that care about it can include a `table` field
+/// on their target type.
+pub fn parse(body: &str) -> Result<Vec<Map<String, Value>>, AnnotatedCsvError> {
+    let mut rows = Vec::new();
+    let mut schema: Option<TableSchema> = None;
+
+    for block in split_into_tables(body) {
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(block.as_bytes());
+
+        // A blank line always starts a fresh table, which must republish its
+        // own annotation and header rows before any data rows.
+        schema = None;
+
+        for record in reader.records() {
+            let record = record.context(CsvSnafu)?;
+
+            if let Some(first) = record.get(0) {
+                if first == "#datatype" {
+                    // The leading, unnamed annotation column has no
+                    // datatype of its own; pad it out so indices below line
+                    // up with `columns`.
+                    schema.get_or_insert_with(TableSchema::default).datatypes =
+                        std::iter::once(None)
+                            .chain(record.iter().skip(1).map(Datatype::parse))
+                            .collect();
+                    continue;
+                }
+                if first == "#group" || (first == "#default" && schema.is_none()) {
+                    // `#group` carries no information we need; an orphaned
+                    // `#default` before `#datatype` shouldn't happen, but
+                    // skip it defensively rather than misreading it as data.
+                    continue;
+                }
+                if first == "#default" {
+                    schema.get_or_insert_with(TableSchema::default).defaults =
+                        std::iter::once(String::new())
+                            .chain(record.iter().skip(1).map(str::to_string))
+                            .collect();
+                    continue;
+                }
+            }
+
+            let table = schema.get_or_insert_with(TableSchema::default);
+            if table.columns.is_empty() {
+                table.columns = record.iter().map(str::to_string).collect();
+                continue;
+            }
+
+            rows.push(parse_data_row(table, &record)?);
+        }
+    }
+
+    Ok(rows)
+}
+
+fn parse_data_row(
+    table: &TableSchema,
+    record: &csv::StringRecord,
+) -> Result<Map<String, Value>, AnnotatedCsvError> {
+    if table.datatypes.is_empty() {
+        return MissingAnnotationsSnafu.fail();
+    }
+
+    let mut row = Map::with_capacity(table.columns.len());
+    for (i, column) in table.columns.iter().enumerate() {
+        let datatype = table.datatypes.get(i).cloned().flatten();
+        let raw = record.get(i).unwrap_or_default();
+        let default = table
+            .defaults
+            .get(i)
+            .map(String::as_str)
+            .unwrap_or_default();
+        let resolved = if raw.is_empty() { default } else { raw };
+
+        let value = match datatype {
+            Some(datatype) => datatype.coerce(column, resolved)?,
+            None => Value::String(resolved.to_string()),
+        };
+        row.insert(column.clone(), value);
+    }
+    Ok(row)
+}
+
+/// Split a response body into the blocks of lines that make up each
+/// independent result table, as delimited by blank lines.
+fn split_into_tables(body: &str) -> impl Iterator<Item = &str> {
+    body.split("\r\n\r\n").flat_map(|b| b.split("\n\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    const RESPONSE: &str = "\
+#datatype,string,long,dateTime:RFC3339,dateTime:RFC3339,dateTime:RFC3339,double,string,string,string
+#group,false,false,true,true,false,false,true,true,true
+#default,_result,,,,,,,,
+,result,table,_start,_stop,_time,_value,_field,_measurement,host
+,_result,0,2021-01-01T00:00:00Z,2021-01-02T00:00:00Z,2021-01-01T00:00:10Z,0.5,usage,cpu,server01
+
+#datatype,string,long,dateTime:RFC3339,dateTime:RFC3339,dateTime:RFC3339,double,string,string,string
+#group,false,false,true,true,false,false,true,true,true
+#default,_result,,,,,,,,
+,result,table,_start,_stop,_time,_value,_field,_measurement,host
+,_result,1,2021-01-01T00:00:00Z,2021-01-02T00:00:00Z,2021-01-01T00:00:20Z,0.87,usage,cpu,server02
+";
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct CpuUsage {
+        table: i64,
+        #[serde(rename = "_time")]
+        time: String,
+        #[serde(rename = "_value")]
+        value: f64,
+        host: String,
+    }
+
+    #[test]
+    fn parses_rows_across_table_boundaries() {
+        let rows = parse(RESPONSE).unwrap();
+        assert_eq!(rows.len(), 2);
+
+        let parsed: Vec<CpuUsage> = rows
+            .into_iter()
+            .map(|row| serde_json::from_value(Value::Object(row)).unwrap())
+            .collect();
+
+        assert_eq!(
+            parsed,
+            vec![
+                CpuUsage {
+                    table: 0,
+                    time: "2021-01-01T00:00:10Z".to_string(),
+                    value: 0.5,
+                    host: "server01".to_string(),
+                },
+                CpuUsage {
+                    table: 1,
+                    time: "2021-01-01T00:00:20Z".to_string(),
+                    value: 0.87,
+                    host: "server02".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_response_has_no_rows() {
+        assert_eq!(parse("").unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn uses_default_for_empty_cells() {
+        let body = "\
+#datatype,string,string
+#group,false,false
+#default,cpu,
+,_measurement,host
+,,server01
+";
+        let rows = parse(body).unwrap();
+        assert_eq!(rows[0]["_measurement"], Value::String("cpu".to_string()));
+        assert_eq!(rows[0]["host"], Value::String("server01".to_string()));
+    }
+
+    #[test]
+    fn keeps_duration_as_its_literal_string() {
+        let body = "\
+#datatype,string,duration
+#group,false,false
+#default,,
+,_measurement,every
+,cpu,1h0m0s
+";
+        let rows = parse(body).unwrap();
+        assert_eq!(rows[0]["every"], Value::String("1h0m0s".to_string()));
+    }
+
+    #[test]
+    fn rejects_non_finite_doubles_instead_of_nulling_them() {
+        let body = "\
+#datatype,string,double
+#group,false,false
+#default,,
+,_measurement,_value
+,cpu,+Inf
+";
+        let err = parse(body).unwrap_err();
+        assert!(matches!(err, AnnotatedCsvError::InvalidValue { .. }));
+    }
+}
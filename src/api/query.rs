@@ -3,15 +3,19 @@
 //! Query InfluxDB using InfluxQL or Flux Query
 
 use crate::{
-    Client, HttpSnafu, RequestError, ReqwestProcessingSnafu, ResponseBytesSnafu,
-    ResponseStringSnafu, SerializingSnafu,
+    AnnotatedCsvSnafu, Client, DeserializingSnafu, HttpSnafu, RequestError, ReqwestProcessingSnafu,
+    ResponseBytesSnafu, ResponseStringSnafu, SerializingSnafu,
 };
 use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 use snafu::ResultExt;
 
+use crate::annotated_csv;
 use crate::models::{
     AnalyzeQueryResponse, AstResponse, FluxSuggestion, FluxSuggestions, LanguageRequest, Query,
 };
+use crate::retry;
 
 impl Client {
     /// Get Query Suggestions
@@ -87,6 +91,61 @@ impl Client {
         }
     }
 
+    /// Like [`Client::query_raw`], but retries transient failures (HTTP
+    /// 429, 503, other 5xx responses, and connection errors) according to
+    /// the client's [`RetryPolicy`]. Retrying is a separate, opt-in method
+    /// here rather than the default, since some Flux queries are expensive
+    /// to re-run.
+    pub async fn query_raw_with_retries(
+        &self,
+        org: &str,
+        query: Option<Query>,
+    ) -> Result<String, RequestError> {
+        let req_url = format!("{}/api/v2/query", self.url);
+        let body = serde_json::to_string(&query.unwrap_or_default()).context(SerializingSnafu)?;
+
+        let response = retry::retry(&self.retry_policy, || {
+            self.request(Method::POST, &req_url)
+                .header("Accepting-Encoding", "identity")
+                .header("Content-Type", "application/json")
+                .query(&[("org", &org)])
+                .body(body.clone())
+                .send()
+        })
+        .await
+        .context(ReqwestProcessingSnafu)?;
+
+        match response.status() {
+            StatusCode::OK => {
+                let bytes = response.bytes().await.context(ResponseBytesSnafu)?;
+                String::from_utf8(bytes.to_vec()).context(ResponseStringSnafu)
+            }
+            status => {
+                let text = response.text().await.context(ReqwestProcessingSnafu)?;
+                HttpSnafu { status, text }.fail()?
+            }
+        }
+    }
+
+    /// Query and deserialize each row of the result into `T`.
+    ///
+    /// This parses the Flux annotated-CSV response into one JSON object per
+    /// data row, coercing each cell according to its `#datatype` annotation,
+    /// then deserializes every row into `T` via `serde`. Callers that want
+    /// the raw response bytes instead should use [`Client::query_raw`].
+    pub async fn query<T>(&self, org: &str, query: Option<Query>) -> Result<Vec<T>, RequestError>
+    where
+        T: DeserializeOwned,
+    {
+        let body = self.query_raw(org, query).await?;
+
+        let rows = annotated_csv::parse(&body).context(AnnotatedCsvSnafu)?;
+
+        rows.into_iter()
+            .map(|row| serde_json::from_value(Value::Object(row)).context(DeserializingSnafu))
+            .collect()
+    }
+
     /// Analyze Query
     pub async fn query_analyze(
         &self,
@@ -148,7 +207,9 @@ impl Client {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::retry::RetryPolicy;
     use mockito::{mock, Matcher};
+    use std::time::Duration;
 
     #[tokio::test]
     async fn query_suggestions() {
@@ -212,6 +273,40 @@ mod tests {
         mock_server.assert();
     }
 
+    #[tokio::test]
+    async fn query_raw_with_retries_retries_on_server_error() {
+        let token = "some-token";
+        let org = "some-org";
+        let query: Option<Query> = Some(Query::new("some-influx-query-string".to_string()));
+
+        let failure = mock("POST", "/api/v2/query")
+            .match_query(Matcher::UrlEncoded("org".into(), org.into()))
+            .with_status(503)
+            .expect(1)
+            .create();
+        let success = mock("POST", "/api/v2/query")
+            .match_query(Matcher::UrlEncoded("org".into(), org.into()))
+            .with_status(200)
+            .with_body("")
+            .create();
+
+        let client = Client::new(mockito::server_url(), token).with_retry_policy(RetryPolicy {
+            max_attempts: 2,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+            jitter: false,
+        });
+
+        let result = client
+            .query_raw_with_retries(org, query)
+            .await
+            .expect("retried request eventually succeeds");
+
+        assert_eq!(result, "");
+        failure.assert();
+        success.assert();
+    }
+
     #[tokio::test]
     async fn query_raw_opt() {
         let token = "some-token";
@@ -322,6 +417,48 @@ mod tests {
         mock_server.assert();
     }
 
+    #[derive(Debug, serde::Deserialize, PartialEq)]
+    struct CpuUsage {
+        host: String,
+        #[serde(rename = "_value")]
+        value: f64,
+    }
+
+    #[tokio::test]
+    async fn query_typed() {
+        let token = "some-token";
+        let org = "some-org";
+        let query: Option<Query> = Some(Query::new("some-influx-query-string".to_string()));
+
+        let mock_server = mock("POST", "/api/v2/query")
+            .match_header("Authorization", format!("Token {}", token).as_str())
+            .match_query(Matcher::UrlEncoded("org".into(), org.into()))
+            .with_body(
+                "\
+#datatype,string,string,double
+#group,false,true,false
+#default,_result,,
+,result,host,_value
+,_result,server01,0.5
+",
+            )
+            .create();
+
+        let client = Client::new(mockito::server_url(), token);
+
+        let result: Vec<CpuUsage> = client.query(org, query).await.expect("request success");
+
+        assert_eq!(
+            result,
+            vec![CpuUsage {
+                host: "server01".to_string(),
+                value: 0.5,
+            }]
+        );
+
+        mock_server.assert();
+    }
+
     #[tokio::test]
     async fn query_raw_no_results() {
         let token = "some-token";
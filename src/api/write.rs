@@ -1,13 +1,18 @@
 //! Write API
 
 use crate::models::WriteDataPoint;
+use crate::retry;
 use crate::{Client, HttpSnafu, RequestError, ReqwestProcessingSnafu};
-use bytes::BufMut;
-use futures::{Stream, StreamExt};
+use bytes::{BufMut, Bytes, BytesMut};
+use flate2::write::GzEncoder;
+use futures::{pin_mut, stream, Stream, StreamExt};
+use reqwest::header::CONTENT_ENCODING;
 use reqwest::{Body, Method};
 use snafu::ResultExt;
 use std::fmt;
 use std::io::{self, Write};
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
 
 /// Timestamp precision
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -50,29 +55,135 @@ impl fmt::Display for Precision {
     }
 }
 
+/// Request body compression used when writing line protocol.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Compression {
+    /// Send the write request body uncompressed.
+    None,
+    /// Gzip-compress the write request body at the given compression level
+    /// (0 through 9, where 0 is no compression and 9 is the best
+    /// compression).
+    Gzip(u32),
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+
+/// Gzip-compresses `data` at the given level. Compressing an in-memory
+/// buffer cannot fail, so this panics rather than returning a `Result`.
+fn gzip_compress(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+    encoder
+        .write_all(data)
+        .expect("writing to an in-memory buffer should not fail");
+    encoder
+        .finish()
+        .expect("finishing an in-memory gzip stream should not fail")
+}
+
+/// Configures how [`Client::write_batched`] splits a point stream into
+/// independent write requests.
+///
+/// A batch is flushed as soon as any one threshold trips, whichever comes
+/// first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchConfig {
+    /// Flush the current batch once it holds this many points.
+    pub max_points: usize,
+    /// Flush the current batch once its serialized line protocol reaches
+    /// this many bytes.
+    pub max_bytes: usize,
+    /// Flush the current batch once it has been open this long, even if
+    /// neither size threshold has tripped.
+    pub max_age: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            // InfluxDB's own recommended batch size for line protocol writes.
+            max_points: 5_000,
+            max_bytes: 10 * 1024 * 1024,
+            max_age: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The outcome of writing a single batch via [`Client::write_batched`].
+#[derive(Debug)]
+pub struct BatchResult {
+    /// Index of this batch, in the order it was flushed, starting at 0.
+    pub batch_index: usize,
+    /// Number of points this batch contained.
+    pub point_count: usize,
+    /// Number of points from earlier batches that were already committed
+    /// before this one was sent, so a caller reacting to a failure knows
+    /// how many points it needs to resend.
+    pub points_committed_before: usize,
+    /// The result of writing this batch.
+    pub result: Result<(), RequestError>,
+}
+
 impl Client {
-    /// Write line protocol data to the specified organization and bucket.
+    /// Write line protocol data to the specified organization and bucket,
+    /// optionally gzip-compressing the request body.
+    ///
+    /// Line-protocol writes are idempotent per point timestamp, so when the
+    /// request body is available as plain bytes (rather than a stream, as
+    /// from [`Client::write`]) this retries transient failures according to
+    /// the client's [`RetryPolicy`](crate::retry::RetryPolicy).
     pub async fn write_line_protocol(
         &self,
         org: &str,
         bucket: &str,
         precision: Precision,
+        compression: Compression,
         body: impl Into<Body> + Send,
     ) -> Result<(), RequestError> {
-        let body = body.into();
+        let mut body = body.into();
+        if let Compression::Gzip(level) = compression {
+            // If the caller already handed us a streaming body (as `write`
+            // does for its incrementally-compressed path below), there are
+            // no bytes to re-compress here; it's already gzip-encoded and we
+            // just need to advertise that with the header below.
+            if let Some(bytes) = body.as_bytes() {
+                body = Body::from(gzip_compress(bytes, level));
+            }
+        }
+
         let write_url = format!("{}/api/v2/write", self.url);
+        let precision = precision.to_string();
+        // A stream body can't be replayed, so only retry when we can
+        // recover the exact bytes that were sent.
+        let retry_bytes = body.as_bytes().map(Bytes::copy_from_slice);
+        let retry_policy = if retry_bytes.is_some() {
+            self.retry_policy
+        } else {
+            retry::RetryPolicy::none()
+        };
 
-        let response = self
-            .request(Method::POST, &write_url)
-            .query(&[
+        let send_request = |body: Body| {
+            let mut request = self.request(Method::POST, &write_url).query(&[
                 ("bucket", bucket),
                 ("org", org),
-                ("precision", precision.to_string().as_str()),
-            ])
-            .body(body)
-            .send()
-            .await
-            .context(ReqwestProcessingSnafu)?;
+                ("precision", precision.as_str()),
+            ]);
+            if let Compression::Gzip(_) = compression {
+                request = request.header(CONTENT_ENCODING, "gzip");
+            }
+            request.body(body).send()
+        };
+
+        let response = match retry_bytes {
+            Some(bytes) => {
+                retry::retry(&retry_policy, || send_request(Body::from(bytes.clone()))).await
+            }
+            None => send_request(body).await,
+        }
+        .context(ReqwestProcessingSnafu)?;
 
         if !response.status().is_success() {
             let status = response.status();
@@ -84,26 +195,187 @@ impl Client {
     }
 
     /// Write a `Stream` of `DataPoint`s to the specified organization and
-    /// bucket.
+    /// bucket, optionally gzip-compressing the request body.
+    ///
+    /// When `compression` is [`Compression::Gzip`], the line protocol is
+    /// compressed incrementally as each point is serialized, rather than
+    /// buffering the whole stream, so memory use stays bounded even for
+    /// long-running or high-volume writes.
     pub async fn write(
         &self,
         org: &str,
         bucket: &str,
         precision: Precision,
+        compression: Compression,
         body: impl Stream<Item = impl WriteDataPoint> + Send + Sync + 'static,
     ) -> Result<(), RequestError> {
         let mut buffer = bytes::BytesMut::new();
 
-        let body = body.map(move |point| {
-            let mut w = (&mut buffer).writer();
-            point.write_data_point_to(&mut w)?;
-            w.flush()?;
-            Ok::<_, io::Error>(buffer.split().freeze())
-        });
+        match compression {
+            Compression::None => {
+                let body = body.map(move |point| {
+                    let mut w = (&mut buffer).writer();
+                    point.write_data_point_to(&mut w)?;
+                    w.flush()?;
+                    Ok::<_, io::Error>(buffer.split().freeze())
+                });
+
+                let body = Body::wrap_stream(body);
+
+                self.write_line_protocol(org, bucket, precision, compression, body)
+                    .await
+            }
+            Compression::Gzip(level) => {
+                let encoder = GzEncoder::new(Vec::new(), flate2::Compression::new(level));
 
-        let body = Body::wrap_stream(body);
+                // Once `body` is exhausted, there's one more chunk to emit:
+                // the gzip trailer (CRC32 + ISIZE) that `encoder.finish()`
+                // writes. `Stream::map` has no hook for "the stream just
+                // ended", so `unfold` drives both the per-point compression
+                // and that final flush through one state machine; `None`
+                // for the encoder marks it as already finished.
+                let body = stream::unfold(
+                    (Box::pin(body), buffer, Some(encoder)),
+                    |(mut points, mut buffer, mut encoder)| async move {
+                        let Some(mut enc) = encoder.take() else {
+                            return None;
+                        };
 
-        self.write_line_protocol(org, bucket, precision, body).await
+                        match points.next().await {
+                            Some(point) => {
+                                let result = (|| {
+                                    let mut w = (&mut buffer).writer();
+                                    point.write_data_point_to(&mut w)?;
+                                    w.flush()?;
+
+                                    let chunk = buffer.split().freeze();
+                                    enc.write_all(&chunk)?;
+                                    enc.flush()?;
+
+                                    Ok::<_, io::Error>(Bytes::from(std::mem::take(enc.get_mut())))
+                                })();
+                                Some((result, (points, buffer, Some(enc))))
+                            }
+                            None => {
+                                let result = enc.finish().map(Bytes::from);
+                                Some((result, (points, buffer, None)))
+                            }
+                        }
+                    },
+                );
+
+                let body = Body::wrap_stream(body);
+
+                self.write_line_protocol(org, bucket, precision, compression, body)
+                    .await
+            }
+        }
+    }
+
+    /// Write a `Stream` of `DataPoint`s as a series of bounded, independent
+    /// write requests instead of a single unbounded one.
+    ///
+    /// The incoming points are accumulated into batches, flushing whichever
+    /// of `config`'s thresholds trips first: point count, serialized byte
+    /// size, or time since the batch was opened. Each batch's result is
+    /// reported individually (in the order flushed) rather than stopping at
+    /// the first failure, so a caller can resume from
+    /// [`BatchResult::points_committed_before`] the failed batch instead of
+    /// losing everything written so far.
+    pub async fn write_batched(
+        &self,
+        org: &str,
+        bucket: &str,
+        precision: Precision,
+        compression: Compression,
+        points: impl Stream<Item = impl WriteDataPoint> + Send,
+        config: BatchConfig,
+    ) -> Vec<BatchResult> {
+        let mut results = Vec::new();
+        let mut buffer = BytesMut::new();
+        let mut point_count = 0usize;
+        let mut points_committed = 0usize;
+        let mut deadline = Instant::now() + config.max_age;
+
+        pin_mut!(points);
+
+        loop {
+            tokio::select! {
+                point = points.next() => {
+                    let Some(point) = point else {
+                        if point_count > 0 {
+                            self.flush_batch(
+                                org, bucket, precision, compression,
+                                &mut buffer, &mut point_count, &mut points_committed, &mut results,
+                            ).await;
+                        }
+                        break;
+                    };
+
+                    if point_count == 0 {
+                        deadline = Instant::now() + config.max_age;
+                    }
+
+                    let mut w = (&mut buffer).writer();
+                    point
+                        .write_data_point_to(&mut w)
+                        .expect("serializing a DataPoint to an in-memory buffer should not fail");
+                    point_count += 1;
+
+                    if point_count >= config.max_points || buffer.len() >= config.max_bytes {
+                        self.flush_batch(
+                            org, bucket, precision, compression,
+                            &mut buffer, &mut point_count, &mut points_committed, &mut results,
+                        ).await;
+                    }
+                }
+                _ = sleep_until(deadline), if point_count > 0 => {
+                    self.flush_batch(
+                        org, bucket, precision, compression,
+                        &mut buffer, &mut point_count, &mut points_committed, &mut results,
+                    ).await;
+                    deadline = Instant::now() + config.max_age;
+                }
+            }
+        }
+
+        results
+    }
+
+    /// Send the accumulated `buffer` as one batch, record its
+    /// [`BatchResult`], and reset the accumulator for the next batch.
+    #[allow(clippy::too_many_arguments)]
+    async fn flush_batch(
+        &self,
+        org: &str,
+        bucket: &str,
+        precision: Precision,
+        compression: Compression,
+        buffer: &mut BytesMut,
+        point_count: &mut usize,
+        points_committed: &mut usize,
+        results: &mut Vec<BatchResult>,
+    ) {
+        let body = buffer.split().freeze();
+        let count = *point_count;
+        let points_committed_before = *points_committed;
+
+        let result = self
+            .write_line_protocol(org, bucket, precision, compression, body)
+            .await;
+
+        if result.is_ok() {
+            *points_committed += count;
+        }
+
+        results.push(BatchResult {
+            batch_index: results.len(),
+            point_count: count,
+            points_committed_before,
+            result,
+        });
+
+        *point_count = 0;
     }
 }
 
@@ -111,27 +383,58 @@ impl Client {
 mod tests {
     use super::*;
     use crate::models::DataPoint;
+    use flate2::read::GzDecoder;
     use futures::stream;
-    use mockito::mock;
+    use mockito::{mock, Matcher};
+    use std::io::Read;
+
+    const EXPECTED_LINE_PROTOCOL: &str = "\
+cpu,host=server01 usage=0.5
+cpu,host=server01,region=us-west usage=0.87
+";
 
     #[tokio::test]
     async fn writing_points() {
+        writing_points_with_compression(Compression::None).await
+    }
+
+    #[tokio::test]
+    async fn writing_points_gzip_compressed() {
+        writing_points_with_compression(Compression::Gzip(6)).await
+    }
+
+    async fn writing_points_with_compression(compression: Compression) {
         let org = "some-org";
         let bucket = "some-bucket";
         let token = "some-token";
 
-        let mock_server = mock(
+        let mut mock_server = mock(
             "POST",
             format!("/api/v2/write?bucket={}&org={}", bucket, org).as_str(),
         )
-        .match_header("Authorization", format!("Token {}", token).as_str())
-        .match_body(
-            "\
-cpu,host=server01 usage=0.5
-cpu,host=server01,region=us-west usage=0.87
-",
-        )
-        .create();
+        .match_header("Authorization", format!("Token {}", token).as_str());
+
+        mock_server = match compression {
+            Compression::None => mock_server.match_body(EXPECTED_LINE_PROTOCOL),
+            // `Matcher::Exact`/`Matcher::Regex` compare against the raw
+            // (still-compressed) body, which isn't useful here since the
+            // same line protocol gzips to different bytes depending on the
+            // encoder's internal chunking. Decode it instead, so this
+            // actually proves the gzip stream is well-formed and round-trips
+            // to the original line protocol, not just that the header is
+            // set.
+            Compression::Gzip(_) => mock_server
+                .match_header("Content-Encoding", "gzip")
+                .match_body(Matcher::Fn(|body| {
+                    let mut decompressed = String::new();
+                    GzDecoder::new(body)
+                        .read_to_string(&mut decompressed)
+                        .map(|_| decompressed == EXPECTED_LINE_PROTOCOL)
+                        .unwrap_or(false)
+                })),
+        };
+
+        let mock_server = mock_server.create();
 
         let client = Client::new(mockito::server_url(), token);
 
@@ -155,9 +458,75 @@ cpu,host=server01,region=us-west usage=0.87
         // provides are much clearer for explaining why a test failed than just
         // that the server returned 501, so don't use `?` here.
         let _result = client
-            .write(org, bucket, Precision::Nanoseconds, stream::iter(points))
+            .write(
+                org,
+                bucket,
+                Precision::Nanoseconds,
+                compression,
+                stream::iter(points),
+            )
             .await;
 
         mock_server.assert();
     }
+
+    #[tokio::test]
+    async fn write_batched_splits_on_max_points() {
+        let org = "some-org";
+        let bucket = "some-bucket";
+        let token = "some-token";
+
+        let mock_server = mock(
+            "POST",
+            format!("/api/v2/write?bucket={}&org={}", bucket, org).as_str(),
+        )
+        .with_status(204)
+        .expect(2)
+        .create();
+
+        let client = Client::new(mockito::server_url(), token);
+
+        let points = vec![
+            DataPoint::builder("cpu")
+                .field("usage", 0.1)
+                .build()
+                .unwrap(),
+            DataPoint::builder("cpu")
+                .field("usage", 0.2)
+                .build()
+                .unwrap(),
+            DataPoint::builder("cpu")
+                .field("usage", 0.3)
+                .build()
+                .unwrap(),
+        ];
+
+        let results = client
+            .write_batched(
+                org,
+                bucket,
+                Precision::Nanoseconds,
+                Compression::None,
+                stream::iter(points),
+                BatchConfig {
+                    max_points: 2,
+                    ..BatchConfig::default()
+                },
+            )
+            .await;
+
+        mock_server.assert();
+
+        assert_eq!(results.len(), 2);
+
+        assert_eq!(results[0].batch_index, 0);
+        assert_eq!(results[0].point_count, 2);
+        assert_eq!(results[0].points_committed_before, 0);
+        assert!(results[0].result.is_ok());
+
+        assert_eq!(results[1].batch_index, 1);
+        assert_eq!(results[1].point_count, 1);
+        assert_eq!(results[1].points_committed_before, 2);
+        assert!(results[1].result.is_ok());
+    }
 }
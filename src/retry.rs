@@ -0,0 +1,180 @@
+//! Retrying transient failures against the InfluxDB HTTP API.
+//!
+//! Rate limiting (HTTP 429), `503`s, and other server errors are common
+//! against InfluxDB Cloud. [`RetryPolicy`] configures how aggressively
+//! [`Client`] re-issues a request that fails with one of those, or with a
+//! transport-level error such as a dropped connection.
+
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Response, StatusCode};
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+
+use crate::Client;
+
+/// Configures retrying of transient failures.
+///
+/// The delay before retry `n` (0-indexed) is `min(max_delay, base_delay *
+/// 2^n)`, further randomized by `jitter`, unless the failed response carried
+/// a `Retry-After` header, in which case that value is used instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts to make, including the first. `1` (as in
+    /// [`RetryPolicy::none`]) disables retrying.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+    /// Upper bound on the computed delay between retries, before jitter is
+    /// applied.
+    pub max_delay: Duration,
+    /// Whether to randomize each delay (uniformly between zero and the
+    /// computed delay) so that many clients retrying at once don't stay in
+    /// lockstep.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries; the request is attempted exactly once.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    fn delay_for(&self, attempt: u32) -> Duration {
+        let exponential = self.base_delay.saturating_mul(1u32 << attempt.min(16));
+        let capped = exponential.min(self.max_delay);
+        if self.jitter && !capped.is_zero() {
+            Duration::from_millis(rand::thread_rng().gen_range(0..=capped.as_millis() as u64))
+        } else {
+            capped
+        }
+    }
+}
+
+impl Client {
+    /// Return a copy of this client configured to retry transient write
+    /// failures (and, where opted into, query failures) according to
+    /// `policy`.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+}
+
+/// Whether an HTTP status code represents a transient failure worth
+/// retrying: rate limiting or a server-side error.
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Whether a `reqwest::Error` represents a transport-level failure (timeout,
+/// connection reset, and the like) worth retrying.
+fn is_retryable_transport_error(err: &reqwest::Error) -> bool {
+    err.is_timeout() || err.is_connect() || err.is_request()
+}
+
+/// Parse a `Retry-After` header value, which per RFC 9110 is either a
+/// non-negative integer number of seconds or an HTTP-date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    httpdate::parse_http_date(value)
+        .ok()
+        .and_then(|when| when.duration_since(SystemTime::now()).ok())
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after)
+}
+
+/// Run `attempt` up to `policy.max_attempts` times, waiting between
+/// retryable failures as described by [`RetryPolicy`].
+///
+/// `attempt` must be safe to call more than once with the same effect, so
+/// it should resend the exact same request body on every call; callers that
+/// can't replay their body (e.g. an in-flight stream) should pass
+/// [`RetryPolicy::none`].
+pub(crate) async fn retry<F, Fut>(policy: &RetryPolicy, mut attempt: F) -> reqwest::Result<Response>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = reqwest::Result<Response>>,
+{
+    let attempts = policy.max_attempts.max(1);
+
+    for attempt_no in 0..attempts {
+        let is_last_attempt = attempt_no + 1 == attempts;
+
+        match attempt().await {
+            Ok(response) if is_last_attempt || !is_retryable_status(response.status()) => {
+                return Ok(response)
+            }
+            Ok(response) => {
+                let delay = retry_after(&response).unwrap_or_else(|| policy.delay_for(attempt_no));
+                tokio::time::sleep(delay).await;
+            }
+            Err(err) if is_last_attempt || !is_retryable_transport_error(&err) => return Err(err),
+            Err(_) => tokio::time::sleep(policy.delay_for(attempt_no)).await,
+        }
+    }
+
+    unreachable!("the last attempt always returns")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_for_grows_exponentially_and_caps() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter: false,
+        };
+
+        assert_eq!(policy.delay_for(0), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(1), Duration::from_millis(200));
+        assert_eq!(policy.delay_for(2), Duration::from_millis(400));
+        assert_eq!(policy.delay_for(10), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn parses_retry_after_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn rejects_garbage_retry_after() {
+        assert_eq!(parse_retry_after("not a valid value"), None);
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_retryable_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!is_retryable_status(StatusCode::BAD_REQUEST));
+        assert!(!is_retryable_status(StatusCode::OK));
+    }
+}
@@ -0,0 +1,465 @@
+//! An in-process test harness for code that uses [`Client`](crate::Client),
+//! enabled by the `test-util` feature.
+//!
+//! Exercising application code that writes points or runs Flux queries
+//! normally means standing up a `mockito` server and hand-matching
+//! `/api/v2/write` and `/api/v2/query` request bodies, exactly as this
+//! crate's own tests do. [`TestClient`] instead records every write
+//! in-process, parsed back into [`RecordedPoint`]s, and lets tests stub
+//! canned responses for the Flux queries they expect, so application code
+//! can be unit-tested without a network mock.
+
+use crate::api::write::{Compression, Precision};
+use crate::models::{Query, WriteDataPoint};
+use crate::{AnnotatedCsvSnafu, DeserializingSnafu, RequestError};
+use bytes::BufMut;
+use futures::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use snafu::ResultExt;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// A field value recorded from a line-protocol write.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Float(f64),
+    Integer(i64),
+    UInteger(u64),
+    Boolean(bool),
+    String(String),
+}
+
+/// A single point recorded by a [`TestClient`] write.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedPoint {
+    /// The bucket the point was written to.
+    pub bucket: String,
+    pub measurement: String,
+    pub tags: BTreeMap<String, String>,
+    pub fields: BTreeMap<String, FieldValue>,
+    pub timestamp: Option<i64>,
+    pub precision: Precision,
+}
+
+/// An in-memory stand-in for [`Client`](crate::Client)'s write and query API.
+///
+/// `TestClient` is cheap to clone; clones share the same recorded writes and
+/// stubbed query responses, so it can be handed to application code the same
+/// way a real `Client` would be.
+#[derive(Debug, Clone, Default)]
+pub struct TestClient {
+    writes: Arc<Mutex<Vec<RecordedPoint>>>,
+    query_responses: Arc<Mutex<BTreeMap<String, String>>>,
+}
+
+impl TestClient {
+    /// Create a `TestClient` with no recorded writes and no stubbed query
+    /// responses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a `Stream` of `DataPoint`s, mirroring
+    /// [`Client::write`](crate::Client::write).
+    pub async fn write(
+        &self,
+        _org: &str,
+        bucket: &str,
+        precision: Precision,
+        _compression: Compression,
+        points: impl Stream<Item = impl WriteDataPoint> + Send,
+    ) -> Result<(), RequestError> {
+        futures::pin_mut!(points);
+
+        while let Some(point) = points.next().await {
+            let mut line = Vec::new();
+            let mut w = (&mut line).writer();
+            point
+                .write_data_point_to(&mut w)
+                .expect("serializing a DataPoint to an in-memory buffer should not fail");
+
+            self.write_line_protocol(bucket, precision, &line_to_string(&line));
+        }
+
+        Ok(())
+    }
+
+    /// Record a raw line-protocol write, mirroring
+    /// [`Client::write_line_protocol`](crate::Client::write_line_protocol).
+    ///
+    /// Each line is parsed back into a [`RecordedPoint`]; a line that isn't
+    /// valid line protocol makes the harness panic, since that means the
+    /// code under test produced a bad write.
+    pub fn write_line_protocol(&self, bucket: &str, precision: Precision, body: &str) {
+        let mut writes = self.writes.lock().expect("writes lock poisoned");
+        for line in body.lines().filter(|line| !line.is_empty()) {
+            writes.push(parse_line(bucket, precision, line));
+        }
+    }
+
+    /// All points recorded so far, across every bucket, in write order.
+    pub fn writes(&self) -> Vec<RecordedPoint> {
+        self.writes.lock().expect("writes lock poisoned").clone()
+    }
+
+    /// The points recorded so far for a single `bucket`, in write order.
+    pub fn writes_to(&self, bucket: &str) -> Vec<RecordedPoint> {
+        self.writes()
+            .into_iter()
+            .filter(|point| point.bucket == bucket)
+            .collect()
+    }
+
+    /// Stub the response for a query, matched on the exact JSON
+    /// serialization of `query` (the same representation sent over the
+    /// wire), mirroring [`Client::query_raw`](crate::Client::query_raw).
+    ///
+    /// `response` can be a plain string or a Flux annotated-CSV document,
+    /// depending on whether the code under test uses
+    /// [`Client::query_raw`](crate::Client::query_raw) or
+    /// [`Client::query`](crate::Client::query).
+    pub fn stub_query(&self, query: Option<&Query>, response: impl Into<String>) {
+        let key = query_key(query);
+        self.query_responses
+            .lock()
+            .expect("query_responses lock poisoned")
+            .insert(key, response.into());
+    }
+
+    /// Return the response stubbed via [`TestClient::stub_query`] for
+    /// `query`, mirroring
+    /// [`Client::query_raw`](crate::Client::query_raw). Panics if no stub
+    /// matches, since an unstubbed query in a test usually means the test
+    /// forgot to call [`TestClient::stub_query`].
+    pub async fn query_raw(
+        &self,
+        _org: &str,
+        query: Option<Query>,
+    ) -> Result<String, RequestError> {
+        let key = query_key(query.as_ref());
+        self.query_responses
+            .lock()
+            .expect("query_responses lock poisoned")
+            .get(&key)
+            .cloned()
+            .map(Ok)
+            .unwrap_or_else(|| {
+                panic!("TestClient has no stubbed response for query {:?}; call TestClient::stub_query first", key)
+            })
+    }
+
+    /// Return the response stubbed via [`TestClient::stub_query`], parsed as
+    /// annotated CSV and deserialized into `T`, mirroring
+    /// [`Client::query`](crate::Client::query).
+    pub async fn query<T>(&self, org: &str, query: Option<Query>) -> Result<Vec<T>, RequestError>
+    where
+        T: DeserializeOwned,
+    {
+        let body = self.query_raw(org, query).await?;
+        let rows = crate::annotated_csv::parse(&body).context(AnnotatedCsvSnafu)?;
+
+        rows.into_iter()
+            .map(|row| {
+                serde_json::from_value(serde_json::Value::Object(row)).context(DeserializingSnafu)
+            })
+            .collect()
+    }
+}
+
+fn query_key(query: Option<&Query>) -> String {
+    serde_json::to_string(&query.cloned().unwrap_or_default())
+        .expect("serializing a Query to JSON should not fail")
+}
+
+fn line_to_string(line: &[u8]) -> String {
+    String::from_utf8(line.to_vec()).expect("line protocol written by a DataPoint is valid UTF-8")
+}
+
+/// Parse one line of line protocol (`measurement[,tag=value...]
+/// field=value[,field=value...] [timestamp]`) into a [`RecordedPoint`].
+fn parse_line(bucket: &str, precision: Precision, line: &str) -> RecordedPoint {
+    // The tag set has no quoting syntax of its own — a `"` there is just a
+    // literal byte, not the start of a quoted span — so split it off with a
+    // plain, quote-unaware space split before switching to quote-aware
+    // scanning for the field set, where a quoted string field value can
+    // legitimately contain a space.
+    let (tag_section, rest) = split_first_unescaped(line, ' ').unwrap_or_else(|| {
+        panic!(
+            "malformed line protocol (expected `measurement[,tags] fields [timestamp]`): {:?}",
+            line
+        )
+    });
+
+    let field_sections = split_unescaped_in_fields(rest, ' ');
+    assert!(
+        field_sections.len() == 1 || field_sections.len() == 2,
+        "malformed line protocol (expected `measurement[,tags] fields [timestamp]`): {:?}",
+        line
+    );
+
+    let mut measurement_and_tags = split_unescaped(tag_section, ',').into_iter();
+    let measurement = unescape(
+        measurement_and_tags
+            .next()
+            .expect("line protocol row has a measurement"),
+    );
+
+    let mut tags = BTreeMap::new();
+    for tag in measurement_and_tags {
+        let (key, value) = split_kv(tag);
+        tags.insert(unescape(key), unescape(value));
+    }
+
+    let mut fields = BTreeMap::new();
+    for field in split_unescaped_in_fields(field_sections[0], ',') {
+        let (key, value) = split_kv(field);
+        fields.insert(unescape(key), parse_field_value(value));
+    }
+
+    let timestamp = field_sections.get(1).map(|t| {
+        t.parse::<i64>()
+            .unwrap_or_else(|_| panic!("malformed line protocol timestamp: {:?}", t))
+    });
+
+    RecordedPoint {
+        bucket: bucket.to_string(),
+        measurement,
+        tags,
+        fields,
+        timestamp,
+        precision,
+    }
+}
+
+fn parse_field_value(value: &str) -> FieldValue {
+    if let Some(stripped) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        return FieldValue::String(stripped.replace("\\\"", "\""));
+    }
+    if let Some(stripped) = value.strip_suffix('i') {
+        return FieldValue::Integer(
+            stripped
+                .parse()
+                .unwrap_or_else(|_| panic!("malformed integer field value: {:?}", value)),
+        );
+    }
+    if let Some(stripped) = value.strip_suffix('u') {
+        return FieldValue::UInteger(
+            stripped
+                .parse()
+                .unwrap_or_else(|_| panic!("malformed unsigned field value: {:?}", value)),
+        );
+    }
+    match value {
+        "t" | "T" | "true" | "True" | "TRUE" => FieldValue::Boolean(true),
+        "f" | "F" | "false" | "False" | "FALSE" => FieldValue::Boolean(false),
+        _ => FieldValue::Float(
+            value
+                .parse()
+                .unwrap_or_else(|_| panic!("malformed float field value: {:?}", value)),
+        ),
+    }
+}
+
+/// Split `kv` at its first unescaped `=`, leaving any further `=` (e.g.
+/// inside a quoted string field value) as part of the value.
+fn split_kv(kv: &str) -> (&str, &str) {
+    split_first_unescaped(kv, '=')
+        .unwrap_or_else(|| panic!("malformed line protocol key=value pair: {:?}", kv))
+}
+
+/// Split `s` at its first unescaped occurrence of `sep` (i.e. not preceded
+/// by a backslash), or `None` if `sep` doesn't occur unescaped.
+fn split_first_unescaped(s: &str, sep: char) -> Option<(&str, &str)> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            _ if c == sep => return Some((&s[..i], &s[i + c.len_utf8()..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split `s` on unescaped occurrences of `sep` (i.e. not preceded by a
+/// backslash), leaving the backslash escapes themselves untouched.
+///
+/// Used for the tag set and measurement name, which have no quoting syntax
+/// of their own: a `"` there is just a literal byte, never the start of a
+/// quoted span.
+fn split_unescaped(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        if c == '\\' {
+            escaped = true;
+        } else if c == sep {
+            parts.push(&s[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// Split `s` on unescaped occurrences of `sep`, the same as
+/// [`split_unescaped`], except occurrences inside an unescaped
+/// double-quoted span are left alone.
+///
+/// Only the field set may contain quoted string field values, where a space
+/// or comma between the quotes is a literal character, not a separator; the
+/// tag set and measurement name have no such quoting and must use
+/// [`split_unescaped`] instead, or a literal `"` in a tag value would be
+/// mistaken for the start of a quoted span.
+fn split_unescaped_in_fields(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    let mut in_quotes = false;
+
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            _ if c == sep && !in_quotes => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                out.push(next);
+                continue;
+            }
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DataPoint;
+    use futures::stream;
+
+    #[tokio::test]
+    async fn records_writes_with_tags_and_fields() {
+        let client = TestClient::new();
+
+        let point = DataPoint::builder("cpu")
+            .tag("host", "server01")
+            .field("usage", 0.5)
+            .build()
+            .unwrap();
+
+        client
+            .write(
+                "some-org",
+                "some-bucket",
+                Precision::Nanoseconds,
+                Compression::None,
+                stream::iter(vec![point]),
+            )
+            .await
+            .unwrap();
+
+        let writes = client.writes_to("some-bucket");
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].measurement, "cpu");
+        assert_eq!(writes[0].tags.get("host"), Some(&"server01".to_string()));
+        assert_eq!(writes[0].fields.get("usage"), Some(&FieldValue::Float(0.5)));
+
+        assert_eq!(client.writes_to("other-bucket"), Vec::new());
+    }
+
+    #[test]
+    fn parses_typed_field_values() {
+        let point = parse_line(
+            "b",
+            Precision::Nanoseconds,
+            r#"m,t=1 count=1i,unsigned=2u,ok=true,name="hi" 100"#,
+        );
+
+        assert_eq!(point.fields.get("count"), Some(&FieldValue::Integer(1)));
+        assert_eq!(point.fields.get("unsigned"), Some(&FieldValue::UInteger(2)));
+        assert_eq!(point.fields.get("ok"), Some(&FieldValue::Boolean(true)));
+        assert_eq!(
+            point.fields.get("name"),
+            Some(&FieldValue::String("hi".to_string()))
+        );
+        assert_eq!(point.timestamp, Some(100));
+    }
+
+    #[test]
+    fn parses_quoted_field_values_with_spaces_and_commas() {
+        let point = parse_line(
+            "b",
+            Precision::Nanoseconds,
+            r#"m f="hello, world" 100"#,
+        );
+
+        assert_eq!(
+            point.fields.get("f"),
+            Some(&FieldValue::String("hello, world".to_string()))
+        );
+        assert_eq!(point.timestamp, Some(100));
+    }
+
+    #[test]
+    fn parses_tag_values_containing_a_literal_quote() {
+        let point = DataPoint::builder("cpu")
+            .tag("host", "a\"b")
+            .field("value", 1.0)
+            .build()
+            .unwrap();
+
+        let mut line = Vec::new();
+        let mut w = (&mut line).writer();
+        point
+            .write_data_point_to(&mut w)
+            .expect("serializing a DataPoint to an in-memory buffer should not fail");
+
+        // `parse_line` takes one line without its terminator, as
+        // `write_line_protocol` passes it after splitting with `lines()`.
+        let point = parse_line("b", Precision::Nanoseconds, line_to_string(&line).trim_end());
+
+        assert_eq!(point.tags.get("host"), Some(&"a\"b".to_string()));
+        assert_eq!(point.fields.get("value"), Some(&FieldValue::Float(1.0)));
+    }
+
+    #[tokio::test]
+    async fn stubs_query_raw_responses() {
+        let client = TestClient::new();
+        let query = Query::new("some-flux-query".to_string());
+
+        client.stub_query(Some(&query), "stubbed response");
+
+        let result = client.query_raw("some-org", Some(query)).await.unwrap();
+        assert_eq!(result, "stubbed response");
+    }
+}